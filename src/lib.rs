@@ -0,0 +1,752 @@
+//! Embeddable core of the fanfare timeseries database.
+//!
+//! This crate owns the on-disk layout (the `Key`/`Code` encoding, and each
+//! series' own schema registry) and exposes it through a [`Writer`]/[`Reader`]
+//! pair, plus two client flavours — [`SyncClient`] (commits every append) and
+//! [`AsyncClient`] (buffers appends and flushes on an interval or on an
+//! explicit [`AsyncClient::commit`]) — for embedders that don't want to
+//! re-derive the validation and ordering rules the `fanfare` CLI enforces.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, Cursor};
+use std::iter::FromIterator;
+use std::mem;
+use std::path::Path;
+use std::str;
+use std::time::{Duration, Instant};
+
+use byteorder::{BigEndian, ReadBytesExt};
+use heed::{types::*, Database, Env, EnvOpenOptions};
+
+pub(crate) type SmallVec8<T> = smallvec::SmallVec<[T; 8]>;
+
+#[derive(Debug)]
+pub enum Error {
+    Heed(heed::Error),
+    Io(io::Error),
+    DatabaseNotFound,
+    InvalidCode,
+    NotOrdered,
+    UnknownSeries,
+    ReservedTimestamp,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Heed(error) => write!(f, "{}", error),
+            Error::Io(error) => write!(f, "{}", error),
+            Error::DatabaseNotFound => write!(f, "database not found"),
+            Error::InvalidCode => write!(f, "the appended values don't match this series' code"),
+            Error::NotOrdered => write!(f, "inserted value not ordered"),
+            Error::UnknownSeries => write!(f, "no schema registered for this series"),
+            Error::ReservedTimestamp => {
+                write!(f, "timestamp 0 is reserved for this series' schema entry")
+            },
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<heed::Error> for Error {
+    fn from(error: heed::Error) -> Error {
+        Error::Heed(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+// Packed integer encoding: unsigned values are LEB128-encoded (7 bits per
+// byte, low-order first, continuation bit set on every byte but the last);
+// signed values are zigzag-mapped to unsigned first so that small magnitude
+// negatives stay small on the wire.
+fn write_uvarint(buffer: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let mut byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value != 0 {
+            byte |= 0x80;
+        }
+        buffer.push(byte);
+        if value == 0 {
+            break;
+        }
+    }
+}
+
+fn read_uvarint<R: io::Read>(reader: &mut R) -> io::Result<u64> {
+    let mut result = 0u64;
+    let mut shift = 0u32;
+    loop {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        result |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    Ok(result)
+}
+
+fn zigzag_encode(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn zigzag_decode(z: u64) -> i64 {
+    ((z >> 1) as i64) ^ -((z & 1) as i64)
+}
+
+/// A single timeseries value.
+///
+/// The character codes are:
+///   * `f` - a 32 bit float (f32)
+///   * `F` - a 64 bit float (f64)
+///   * `u` - a 32 bit unsigned integer (u32)
+///   * `U` - a 64 bit unsigned integer (u64)
+///   * `i` - a 32 bit signed integer (i32)
+///   * `I` - a 64 bit signed integer (i64)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Float(f32),
+    Double(f64),
+    Unsigned(u32),
+    UnsignedLong(u64),
+    Signed(i32),
+    SignedLong(i64),
+}
+
+impl Value {
+    pub fn code(self) -> u8 {
+        match self {
+            Value::Float(_) => b'f',
+            Value::Double(_) => b'F',
+            Value::Unsigned(_) => b'u',
+            Value::UnsignedLong(_) => b'U',
+            Value::Signed(_) => b'i',
+            Value::SignedLong(_) => b'I',
+        }
+    }
+
+    fn encode(self, buffer: &mut Vec<u8>, packed: bool) {
+        match self {
+            Value::Float(n) => buffer.extend_from_slice(&n.to_be_bytes()),
+            Value::Double(n) => buffer.extend_from_slice(&n.to_be_bytes()),
+            Value::Unsigned(n) if packed => write_uvarint(buffer, n as u64),
+            Value::Unsigned(n) => buffer.extend_from_slice(&n.to_be_bytes()),
+            Value::UnsignedLong(n) if packed => write_uvarint(buffer, n),
+            Value::UnsignedLong(n) => buffer.extend_from_slice(&n.to_be_bytes()),
+            Value::Signed(n) if packed => write_uvarint(buffer, zigzag_encode(n as i64)),
+            Value::Signed(n) => buffer.extend_from_slice(&n.to_be_bytes()),
+            Value::SignedLong(n) if packed => write_uvarint(buffer, zigzag_encode(n)),
+            Value::SignedLong(n) => buffer.extend_from_slice(&n.to_be_bytes()),
+        }
+    }
+
+    fn decode(code: u8, packed: bool, cursor: &mut Cursor<&[u8]>) -> io::Result<Value> {
+        match (code, packed) {
+            (b'f', _) => Ok(Value::Float(cursor.read_f32::<BigEndian>()?)),
+            (b'F', _) => Ok(Value::Double(cursor.read_f64::<BigEndian>()?)),
+            (b'u', true) => Ok(Value::Unsigned(read_uvarint(cursor)? as u32)),
+            (b'u', false) => Ok(Value::Unsigned(cursor.read_u32::<BigEndian>()?)),
+            (b'U', true) => Ok(Value::UnsignedLong(read_uvarint(cursor)?)),
+            (b'U', false) => Ok(Value::UnsignedLong(cursor.read_u64::<BigEndian>()?)),
+            (b'i', true) => Ok(Value::Signed(zigzag_decode(read_uvarint(cursor)?) as i32)),
+            (b'i', false) => Ok(Value::Signed(cursor.read_i32::<BigEndian>()?)),
+            (b'I', true) => Ok(Value::SignedLong(zigzag_decode(read_uvarint(cursor)?))),
+            (b'I', false) => Ok(Value::SignedLong(cursor.read_i64::<BigEndian>()?)),
+            (c, _) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid value code `{}`", c as char),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Float(n) => write!(f, "{}", n),
+            Value::Double(n) => write!(f, "{}", n),
+            Value::Unsigned(n) => write!(f, "{}", n),
+            Value::UnsignedLong(n) => write!(f, "{}", n),
+            Value::Signed(n) => write!(f, "{}", n),
+            Value::SignedLong(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+/// A decoded row, as yielded by [`Reader::range`]/[`Reader::filter`].
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub series: String,
+    pub timestamp: u64,
+    pub values: SmallVec8<Value>,
+}
+
+pub(crate) struct Key;
+
+impl<'a> heed::BytesEncode<'a> for Key {
+    type EItem = (&'a str, u64);
+
+    fn bytes_encode((text, nanos): &Self::EItem) -> Option<Cow<[u8]>> {
+        let mut buffer = Vec::with_capacity(text.len() + mem::size_of::<u64>());
+        buffer.extend_from_slice(text.as_bytes());
+        buffer.extend_from_slice(&nanos.to_be_bytes());
+        Some(Cow::Owned(buffer))
+    }
+}
+
+impl<'a> heed::BytesDecode<'a> for Key {
+    type DItem = (&'a str, u64);
+
+    fn bytes_decode(bytes: &'a [u8]) -> Option<Self::DItem> {
+        let text_len = bytes.len() - mem::size_of::<u64>();
+        let text = str::from_utf8(&bytes[..text_len]).ok()?;
+
+        let nanos_bytes = &bytes[text_len..];
+        let nanos_array = nanos_bytes.try_into().ok()?;
+        let nanos = u64::from_be_bytes(nanos_array);
+
+        Some((text, nanos))
+    }
+}
+
+const MAP_SIZE: usize = 10 * 1024 * 1024 * 1024; // 10GB
+
+// Every series gets its own schema: the code for series `s` is stored under
+// the reserved key `(s, SCHEMA_NANOS)`, the same way nanos `1` under the
+// empty series name is reserved for the DB-wide packed flag (see
+// `Writer::open`).
+// `timestamp_nanos == SCHEMA_NANOS` is therefore not a valid row timestamp
+// for any series; `Writer::append` rejects it with `Error::ReservedTimestamp`
+// instead of silently colliding with the schema entry.
+const SCHEMA_NANOS: u64 = 0;
+
+/// A source of the current time, expressed as nanoseconds since the Unix
+/// epoch. Exists so that server-side timestamping doesn't hard-wire the
+/// wall clock: tests can inject a deterministic, monotonically increasing
+/// clock to exercise [`Writer::append`]'s ordering invariant without
+/// sleeping or depending on real time.
+pub trait Clocks {
+    fn now(&self) -> u64;
+}
+
+/// The real wall clock, expressed as nanoseconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clocks for SystemClock {
+    fn now(&self) -> u64 {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system clock is before the Unix epoch")
+            .as_nanos() as u64
+    }
+}
+
+/// Owns the write side of a database: validates appended rows against their
+/// own series' value code (each series gets its own, so `oceanic-airlines`
+/// can be `ff` while another series is `UI` in the same database) and
+/// enforces the append-only (timestamp-ordered) invariant on a per-series
+/// basis.
+///
+/// Rows are written with `Database::put` rather than `Database::append`:
+/// `append` requires every key in the *whole* database to arrive in strictly
+/// increasing order, which different series interleaved by wall-clock time
+/// would violate even though each series is individually in order. Instead,
+/// `Writer` tracks the last timestamp it has seen per series and rejects
+/// anything that doesn't increase on it.
+pub struct Writer {
+    env: Env,
+    db: Database<Key, ByteSlice>,
+    schemas: HashMap<String, Vec<u8>>,
+    last_nanos: HashMap<String, u64>,
+    packed: bool,
+}
+
+impl Writer {
+    /// Opens (creating if necessary) the database at `path`.
+    ///
+    /// `packed` only takes effect while the database is empty: a packed
+    /// database records the choice on disk, and a database that already
+    /// holds entries without that record is read as unpacked. Either way,
+    /// `packed` is then ignored.
+    pub fn open<P: AsRef<Path>>(path: P, packed: bool) -> Result<Writer, Error> {
+        let env = EnvOpenOptions::new().map_size(MAP_SIZE).open(path)?;
+        let db = env.create_database::<Key, ByteSlice>(None)?;
+
+        let mut wtxn = env.write_txn()?;
+        let packed = match db.get(&wtxn, &("", 1))? {
+            Some(bytes) => bytes.first().copied().unwrap_or(0) != 0,
+            None => {
+                // Entries already on disk without the flag were written unpacked.
+                let packed = packed && db.first(&wtxn)?.is_none();
+                if packed {
+                    db.put(&mut wtxn, &("", 1), &[1u8])?;
+                }
+                packed
+            },
+        };
+        wtxn.commit()?;
+
+        Ok(Writer { env, db, schemas: HashMap::new(), last_nanos: HashMap::new(), packed })
+    }
+
+    pub fn packed(&self) -> bool {
+        self.packed
+    }
+
+    /// Appends `values` for `series` at `timestamp_nanos`, committing
+    /// immediately.
+    pub fn append(&mut self, series: &str, timestamp_nanos: u64, values: &[Value]) -> Result<(), Error> {
+        let mut wtxn = self.env.write_txn()?;
+        Writer::append_in_txn(
+            &self.db,
+            &mut self.schemas,
+            &mut self.last_nanos,
+            self.packed,
+            &mut wtxn,
+            series,
+            timestamp_nanos,
+            values,
+        )?;
+        wtxn.commit()?;
+        Ok(())
+    }
+
+    /// Appends `values` for `series`, stamping them with `clock.now()`
+    /// instead of a caller-supplied timestamp.
+    pub fn append_now(&mut self, series: &str, values: &[Value], clock: &dyn Clocks) -> Result<(), Error> {
+        self.append(series, clock.now(), values)
+    }
+
+    /// Appends every row in `rows` inside a single transaction.
+    pub fn append_batch<'a, I>(&mut self, rows: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = (&'a str, u64, &'a [Value])>,
+    {
+        let mut wtxn = self.env.write_txn()?;
+        for (series, timestamp_nanos, values) in rows {
+            Writer::append_in_txn(
+                &self.db,
+                &mut self.schemas,
+                &mut self.last_nanos,
+                self.packed,
+                &mut wtxn,
+                series,
+                timestamp_nanos,
+                values,
+            )?;
+        }
+        wtxn.commit()?;
+        Ok(())
+    }
+
+    // Takes `db`/`schemas`/`last_nanos`/`packed` as separate parameters,
+    // rather than `&mut self`, so callers can hold `wtxn` (itself borrowed
+    // from `self.env`) and this helper at the same time.
+    fn append_in_txn(
+        db: &Database<Key, ByteSlice>,
+        schemas: &mut HashMap<String, Vec<u8>>,
+        last_nanos: &mut HashMap<String, u64>,
+        packed: bool,
+        wtxn: &mut heed::RwTxn,
+        series: &str,
+        timestamp_nanos: u64,
+        values: &[Value],
+    ) -> Result<(), Error> {
+        if timestamp_nanos == SCHEMA_NANOS {
+            return Err(Error::ReservedTimestamp);
+        }
+
+        let code: Vec<u8> = values.iter().map(|value| value.code()).collect();
+
+        if !schemas.contains_key(series) {
+            match db.get(wtxn, &(series, SCHEMA_NANOS))? {
+                Some(existing) => {
+                    schemas.insert(series.to_owned(), existing.to_owned());
+                },
+                None => {
+                    db.put(wtxn, &(series, SCHEMA_NANOS), &code)?;
+                    schemas.insert(series.to_owned(), code.clone());
+                },
+            }
+        }
+
+        if schemas[series] != code {
+            return Err(Error::InvalidCode);
+        }
+
+        // Tracked per series rather than relying on `Database::append`'s
+        // whole-database ordering (see the struct doc comment). The first
+        // time a series is seen in this `Writer`'s lifetime, fall back to
+        // the last row already on disk for it, if any.
+        if !last_nanos.contains_key(series) {
+            let start = (series, SCHEMA_NANOS + 1);
+            let end = (series, u64::max_value());
+            if let Some(((_, nanos), _)) = db.range(wtxn, &(start..=end))?.last().transpose()? {
+                last_nanos.insert(series.to_owned(), nanos);
+            }
+        }
+
+        if let Some(&last) = last_nanos.get(series) {
+            if timestamp_nanos <= last {
+                return Err(Error::NotOrdered);
+            }
+        }
+
+        let mut buffer = Vec::new();
+        for value in values {
+            value.encode(&mut buffer, packed);
+        }
+
+        db.put(wtxn, &(series, timestamp_nanos), &buffer)?;
+        last_nanos.insert(series.to_owned(), timestamp_nanos);
+
+        Ok(())
+    }
+}
+
+/// Commits on every [`SyncClient::append`] — favours durability over
+/// throughput.
+pub struct SyncClient {
+    writer: Writer,
+}
+
+impl SyncClient {
+    pub fn open<P: AsRef<Path>>(path: P, packed: bool) -> Result<SyncClient, Error> {
+        Ok(SyncClient { writer: Writer::open(path, packed)? })
+    }
+
+    pub fn append(&mut self, series: &str, timestamp_nanos: u64, values: &[Value]) -> Result<(), Error> {
+        self.writer.append(series, timestamp_nanos, values)
+    }
+}
+
+/// Buffers appends in memory and flushes them in a single transaction once
+/// `flush_interval` has elapsed since the last flush, or when
+/// [`AsyncClient::commit`] is called explicitly — favours throughput over
+/// durability.
+pub struct AsyncClient {
+    writer: Writer,
+    pending: Vec<(String, u64, SmallVec8<Value>)>,
+    flush_interval: Duration,
+    last_flush: Instant,
+}
+
+impl AsyncClient {
+    pub fn open<P: AsRef<Path>>(path: P, packed: bool, flush_interval: Duration) -> Result<AsyncClient, Error> {
+        Ok(AsyncClient {
+            writer: Writer::open(path, packed)?,
+            pending: Vec::new(),
+            flush_interval,
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Buffers `values` for `series` at `timestamp_nanos`. Triggers a
+    /// [`commit`](AsyncClient::commit) if `flush_interval` has elapsed since
+    /// the last one.
+    pub fn append(&mut self, series: &str, timestamp_nanos: u64, values: &[Value]) -> Result<(), Error> {
+        self.pending.push((series.to_owned(), timestamp_nanos, SmallVec8::from_iter(values.iter().copied())));
+
+        if self.last_flush.elapsed() >= self.flush_interval {
+            self.commit()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes every buffered row to the database in a single transaction.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        let rows = self.pending.iter().map(|(series, nanos, values)| (series.as_str(), *nanos, values.as_slice()));
+        self.writer.append_batch(rows)?;
+        self.pending.clear();
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+/// Read side of a database: exposes each series' own schema, the DB-wide
+/// packed-ness flag, and timestamp-ordered iteration over a series' rows —
+/// decoding every row using the schema registered for *its* series rather
+/// than a single database-wide code.
+pub struct Reader {
+    db: Database<Key, ByteSlice>,
+    env: Env,
+}
+
+impl Reader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Reader, Error> {
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .map_size(MAP_SIZE)
+                .flag(heed::flags::Flags::MdbRdOnly)
+                .open(path)?
+        };
+
+        let db = match env.open_database::<Key, ByteSlice>(None)? {
+            Some(db) => db,
+            None => return Err(Error::DatabaseNotFound),
+        };
+
+        Ok(Reader { env, db })
+    }
+
+    pub fn read_txn(&self) -> Result<heed::RoTxn, Error> {
+        Ok(self.env.read_txn()?)
+    }
+
+    /// The value code registered for `series`, e.g. `ff` for two `f32`
+    /// columns, or `None` if nothing has ever been appended to it.
+    pub fn schema(&self, rtxn: &heed::RoTxn, series: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.db.get(rtxn, &(series, SCHEMA_NANOS))?.map(ToOwned::to_owned))
+    }
+
+    /// Every series that has a schema registered, alongside its code.
+    pub fn schemas(&self, rtxn: &heed::RoTxn) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let mut schemas = Vec::new();
+        for result in self.db.iter(rtxn)? {
+            let ((text, nanos), code) = result?;
+            if nanos == SCHEMA_NANOS && !text.is_empty() {
+                schemas.push((text.to_owned(), code.to_owned()));
+            }
+        }
+        Ok(schemas)
+    }
+
+    pub fn packed(&self, rtxn: &heed::RoTxn) -> Result<bool, Error> {
+        Ok(self.db.get(rtxn, &("", 1))?.and_then(|b| b.first().copied()).unwrap_or(0) != 0)
+    }
+
+    /// The number of data rows, excluding every series' schema entry and the
+    /// DB-wide reserved entries under the empty series name.
+    pub fn len(&self, rtxn: &heed::RoTxn) -> Result<u64, Error> {
+        let mut len = 0u64;
+        for result in self.db.iter(rtxn)? {
+            let ((text, nanos), _) = result?;
+            if nanos == SCHEMA_NANOS || (text.is_empty() && nanos == 1) {
+                continue;
+            }
+            len += 1;
+        }
+        Ok(len)
+    }
+
+    /// Iterates every row, in timestamp order, whose series name exactly
+    /// matches `series`. Yields nothing if `series` has no schema yet.
+    pub fn range<'t>(
+        &self,
+        rtxn: &'t heed::RoTxn,
+        series: &str,
+    ) -> Result<Box<dyn Iterator<Item = Result<Record, Error>> + 't>, Error> {
+        let code = match self.schema(rtxn, series)? {
+            Some(code) => code,
+            None => return Ok(Box::new(std::iter::empty())),
+        };
+        let packed = self.packed(rtxn)?;
+        let start = (series, SCHEMA_NANOS + 1);
+        let end = (series, u64::max_value());
+        let iter = self.db.range(rtxn, &(start..=end))?;
+        Ok(Box::new(decode_iter(iter, move |_| Ok(code.clone()), packed)))
+    }
+
+    /// Iterates every row whose series name matches `pattern`, decoding each
+    /// one with its own series' schema. Rows come in key order: grouped by
+    /// series name, and in timestamp order within each series.
+    pub fn filter<'t>(
+        &self,
+        rtxn: &'t heed::RoTxn,
+        pattern: glob::Pattern,
+    ) -> Result<Box<dyn Iterator<Item = Result<Record, Error>> + 't>, Error> {
+        let packed = self.packed(rtxn)?;
+        let iter = self.db.range(rtxn, &(("", 2)..))?;
+        let db = self.db;
+        let schema_of = move |series: &str| {
+            db.get(rtxn, &(series, SCHEMA_NANOS))?.map(ToOwned::to_owned).ok_or(Error::UnknownSeries)
+        };
+
+        Ok(Box::new(decode_iter(iter, schema_of, packed).filter(move |result| match result {
+            Ok(record) => pattern.matches(&record.series),
+            Err(_) => true,
+        })))
+    }
+}
+
+fn decode_iter<'t, I, F>(iter: I, mut schema_of: F, packed: bool) -> impl Iterator<Item = Result<Record, Error>> + 't
+where
+    I: Iterator<Item = Result<((&'t str, u64), &'t [u8]), heed::Error>> + 't,
+    F: FnMut(&str) -> Result<Vec<u8>, Error> + 't,
+{
+    // Consecutive rows usually belong to the same series (keys are ordered
+    // by series first), so a one-entry cache avoids a schema lookup per row.
+    let mut cache: Option<(String, Vec<u8>)> = None;
+
+    iter.filter_map(move |result| {
+        let ((text, nanos), bytes) = match result {
+            Ok(entry) => entry,
+            Err(error) => return Some(Err(error.into())),
+        };
+
+        if nanos == SCHEMA_NANOS {
+            return None;
+        }
+
+        let code = match &cache {
+            Some((cached_series, code)) if cached_series == text => code.clone(),
+            _ => match schema_of(text) {
+                Ok(code) => {
+                    cache = Some((text.to_owned(), code.clone()));
+                    code
+                },
+                Err(error) => return Some(Err(error)),
+            },
+        };
+
+        let mut cursor = Cursor::new(bytes);
+        let mut values = SmallVec8::new();
+        for &c in &code {
+            match Value::decode(c, packed, &mut cursor) {
+                Ok(value) => values.push(value),
+                Err(error) => return Some(Err(error.into())),
+            }
+        }
+
+        Some(Ok(Record { series: text.to_owned(), timestamp: nanos, values }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::path::PathBuf;
+
+    /// A fresh, empty directory under the system temp dir for a test's LMDB
+    /// environment, named after the test so concurrent tests don't collide.
+    fn temp_db_dir(name: &str) -> PathBuf {
+        let mut dir = std::env::temp_dir();
+        dir.push(format!("fanfare-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A clock that advances by one nanosecond on every call, so tests can
+    /// exercise the append-ordering invariant without sleeping.
+    struct StepClock(Cell<u64>);
+
+    impl StepClock {
+        fn starting_at(nanos: u64) -> StepClock {
+            StepClock(Cell::new(nanos))
+        }
+    }
+
+    impl Clocks for StepClock {
+        fn now(&self) -> u64 {
+            let nanos = self.0.get();
+            self.0.set(nanos + 1);
+            nanos
+        }
+    }
+
+    /// A clock that always returns the same timestamp, to drive the
+    /// `NotOrdered` branch on a second append.
+    struct FixedClock(u64);
+
+    impl Clocks for FixedClock {
+        fn now(&self) -> u64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn append_now_advances_with_the_injected_clock() {
+        let dir = temp_db_dir("append_now_advances_with_the_injected_clock");
+        let mut writer = Writer::open(&dir, false).unwrap();
+        let clock = StepClock::starting_at(1);
+
+        writer.append_now("temperature", &[Value::Float(1.0)], &clock).unwrap();
+        writer.append_now("temperature", &[Value::Float(2.0)], &clock).unwrap();
+
+        let reader = Reader::open(&dir).unwrap();
+        let rtxn = reader.read_txn().unwrap();
+        assert_eq!(reader.len(&rtxn).unwrap(), 2);
+    }
+
+    #[test]
+    fn append_now_rejects_a_non_increasing_timestamp() {
+        let dir = temp_db_dir("append_now_rejects_a_non_increasing_timestamp");
+        let mut writer = Writer::open(&dir, false).unwrap();
+        let clock = FixedClock(42);
+
+        writer.append_now("temperature", &[Value::Float(1.0)], &clock).unwrap();
+        let result = writer.append_now("temperature", &[Value::Float(2.0)], &clock);
+
+        assert!(matches!(result, Err(Error::NotOrdered)));
+    }
+
+    #[test]
+    fn every_value_variant_roundtrips_packed_and_unpacked() {
+        let values = [
+            Value::Float(1.5),
+            Value::Double(-2.25),
+            Value::Unsigned(u32::max_value()),
+            Value::UnsignedLong(u64::max_value()),
+            Value::Signed(i32::min_value()),
+            Value::SignedLong(i64::min_value()),
+        ];
+
+        for packed in [false, true] {
+            for &value in &values {
+                let mut buffer = Vec::new();
+                value.encode(&mut buffer, packed);
+                let mut cursor = Cursor::new(buffer.as_slice());
+                let decoded = Value::decode(value.code(), packed, &mut cursor).unwrap();
+                assert_eq!(decoded, value);
+            }
+        }
+    }
+
+    #[test]
+    fn each_series_keeps_its_own_schema() {
+        let dir = temp_db_dir("each_series_keeps_its_own_schema");
+        let mut writer = Writer::open(&dir, false).unwrap();
+
+        // Deliberately out of lexicographic order across series: "errors" <
+        // "oceanic-airlines", which would trip `Database::append`'s
+        // whole-database ordering requirement if `Writer` still used it.
+        writer.append("oceanic-airlines", 1, &[Value::Float(1.0), Value::Float(2.0)]).unwrap();
+        writer.append("errors", 1, &[Value::UnsignedLong(1), Value::Signed(-1)]).unwrap();
+
+        let reader = Reader::open(&dir).unwrap();
+        let rtxn = reader.read_txn().unwrap();
+
+        assert_eq!(reader.schema(&rtxn, "oceanic-airlines").unwrap(), Some(b"ff".to_vec()));
+        assert_eq!(reader.schema(&rtxn, "errors").unwrap(), Some(b"Ui".to_vec()));
+
+        let rows: Vec<Record> = reader.range(&rtxn, "errors").unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].values.to_vec(), vec![Value::UnsignedLong(1), Value::Signed(-1)]);
+    }
+
+    #[test]
+    fn append_rejects_the_reserved_schema_timestamp() {
+        let dir = temp_db_dir("append_rejects_the_reserved_schema_timestamp");
+        let mut writer = Writer::open(&dir, false).unwrap();
+
+        let result = writer.append("temperature", 0, &[Value::Float(1.0)]);
+
+        assert!(matches!(result, Err(Error::ReservedTimestamp)));
+    }
+}
@@ -1,54 +1,16 @@
-use std::borrow::Cow;
-use std::convert::TryInto;
-use std::io::{self, Write, BufRead, BufReader, BufWriter, Cursor};
-use std::iter::FromIterator;
-use std::mem;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
 use std::path::PathBuf;
-use std::str::{self, FromStr};
+use std::str::FromStr;
 
-use byteorder::{BigEndian, ReadBytesExt};
 use chrono::NaiveDateTime;
-use heed::{EnvOpenOptions, Error, LmdbError};
-use heed::types::*;
+use fanfare::{Reader, SystemClock, Value, Writer};
 use main_error::MainError;
+use serde_json::json;
 use structopt::StructOpt;
 
 const ONE_BILLION: u64 = 1_000_000_000;
 const DATETIME_FORMAT: &str = "%FT%T%.f";
 
-type SmallVec8<T> = smallvec::SmallVec<[T; 8]>;
-
-// The character codes are:
-//   * `f` - a 32 bit float (f32)
-//   * `F` - a 64 bit float (f64)
-//   * `u` - a 32 bit unsigned integer (u32)
-//   * `U` - a 64 bit unsigned integer (u64)
-//   * `i` - a 32 bit signed integer (i32)
-//   * `I` - a 64 bit signed integer (i64)
-#[derive(Debug, Clone, Copy)]
-enum Code {
-    Float,
-    Double,
-    Unsigned,
-    UnsignedLong,
-    Signed,
-    SignedLong,
-}
-
-impl Code {
-    fn from(c: u8) -> Option<Code> {
-        match c {
-            b'f' => Some(Code::Float),
-            b'F' => Some(Code::Double),
-            b'u' => Some(Code::Unsigned),
-            b'U' => Some(Code::UnsignedLong),
-            b'i' => Some(Code::Signed),
-            b'I' => Some(Code::SignedLong),
-            _ => None,
-        }
-    }
-}
-
 #[derive(StructOpt)]
 #[structopt(about = "The fanfare timeseries database.")]
 enum Opt {
@@ -61,6 +23,14 @@ enum Opt {
 struct WriteOpt {
     #[structopt(short, long, parse(from_os_str))]
     database: PathBuf,
+    /// Pack `u`/`U`/`i`/`I` values as LEB128 varints (signed values are
+    /// zigzag-mapped first) instead of fixed-width big-endian integers.
+    #[structopt(long)]
+    packed: bool,
+    /// Stamp incoming lines with the current time instead of reading a date
+    /// column, so lines look like `<text> <code> <values...>`.
+    #[structopt(long)]
+    auto_timestamp: bool,
 }
 
 #[derive(StructOpt)]
@@ -69,241 +39,193 @@ struct ReadOpt {
     database: PathBuf,
     #[structopt(long)]
     filter: Option<glob::Pattern>,
+    #[structopt(long, default_value = "text", possible_values = &["text", "cbor", "json"])]
+    format: Format,
 }
 
-#[derive(StructOpt)]
-struct InfosOpt {
-    #[structopt(short, long, parse(from_os_str))]
-    database: PathBuf,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Cbor,
+    Json,
 }
 
-struct Key;
+impl FromStr for Format {
+    type Err = String;
 
-impl<'a> heed::BytesEncode<'a> for Key {
-    type EItem = (&'a str, u64);
-
-    fn bytes_encode((text, nanos): &Self::EItem) -> Option<Cow<[u8]>> {
-        let mut buffer = Vec::with_capacity(text.len() + mem::size_of::<u64>());
-        buffer.extend_from_slice(text.as_bytes());
-        buffer.extend_from_slice(&nanos.to_be_bytes());
-        Some(Cow::Owned(buffer))
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "cbor" => Ok(Format::Cbor),
+            "json" => Ok(Format::Json),
+            otherwise => Err(format!("invalid format: {}", otherwise)),
+        }
     }
 }
 
-impl<'a> heed::BytesDecode<'a> for Key {
-    type DItem = (&'a str, u64);
+fn json_value(value: Value) -> serde_json::Value {
+    match value {
+        Value::Float(n) => json!(n),
+        Value::Double(n) => json!(n),
+        Value::Unsigned(n) => json!(n),
+        Value::UnsignedLong(n) => json!(n),
+        Value::Signed(n) => json!(n),
+        Value::SignedLong(n) => json!(n),
+    }
+}
 
-    fn bytes_decode(bytes: &'a [u8]) -> Option<Self::DItem> {
-        let text_len = bytes.len() - mem::size_of::<u64>();
-        let text = str::from_utf8(&bytes[..text_len]).ok()?;
+fn cbor_value(value: Value) -> serde_cbor::Value {
+    match value {
+        Value::Float(n) => serde_cbor::Value::Float(n as f64),
+        Value::Double(n) => serde_cbor::Value::Float(n),
+        Value::Unsigned(n) => serde_cbor::Value::Integer(n as i128),
+        Value::UnsignedLong(n) => serde_cbor::Value::Integer(n as i128),
+        Value::Signed(n) => serde_cbor::Value::Integer(n as i128),
+        Value::SignedLong(n) => serde_cbor::Value::Integer(n as i128),
+    }
+}
 
-        let nanos_bytes = &bytes[text_len..];
-        let nanos_array = nanos_bytes.try_into().ok()?;
-        let nanos = u64::from_be_bytes(nanos_array);
+#[derive(StructOpt)]
+struct InfosOpt {
+    #[structopt(short, long, parse(from_os_str))]
+    database: PathBuf,
+}
 
-        Some((text, nanos))
+fn parse_value(code: u8, text: &str) -> Result<Value, MainError> {
+    match code {
+        b'f' => Ok(Value::Float(f32::from_str(text)?)),
+        b'F' => Ok(Value::Double(f64::from_str(text)?)),
+        b'u' => Ok(Value::Unsigned(u32::from_str(text)?)),
+        b'U' => Ok(Value::UnsignedLong(u64::from_str(text)?)),
+        b'i' => Ok(Value::Signed(i32::from_str(text)?)),
+        b'I' => Ok(Value::SignedLong(i64::from_str(text)?)),
+        _ => Err("Invalid code character".into()),
     }
 }
 
 fn write_to_database(opt: WriteOpt) -> Result<(), MainError> {
-    let env = EnvOpenOptions::new()
-        .map_size(10 * 1024 * 1024 * 1024) // 10GB
-        .open(opt.database)?;
+    let mut writer = Writer::open(opt.database, opt.packed)?;
 
-    let db = env.create_database::<Key, ByteSlice>(None)?;
-    let mut wtxn = env.write_txn()?;
-
-    let mut values_code = db.get(&wtxn, &("", 0))?.map(ToOwned::to_owned);
-
-    let mut buffer = Vec::new();
     let reader = BufReader::new(io::stdin());
+    let mut values = Vec::new();
+    let clock = SystemClock;
 
     for result in reader.lines() {
         let line = result?;
-        buffer.clear();
+        values.clear();
 
         let mut iter = line.split_whitespace();
         let text = iter.next().ok_or("missing text")?;
-        let date = iter.next().ok_or("missing date")?;
+        let date = if opt.auto_timestamp { None } else { Some(iter.next().ok_or("missing date")?) };
         let code = iter.next().ok_or("missing code")?;
-        let values = iter.clone();
-
-        let code = match values_code {
-            Some(ref old_code) if &old_code[..] == code.as_bytes() => code,
-            Some(_) => return Err("invalid code".into()),
-            None => {
-                db.put(&mut wtxn, &("", 0), code.as_bytes())?;
-                values_code = Some(code.as_bytes().to_owned());
-                code
-            },
-        };
 
-        if code.len() != iter.count() {
+        if code.len() != iter.clone().count() {
             return Err("wrong number of values".into());
         }
 
-        let dt = NaiveDateTime::parse_from_str(date, DATETIME_FORMAT)?;
-        let nanos = dt.timestamp_nanos() as u64;
-
-        for (c, n) in code.as_bytes().iter().zip(values) {
-            match Code::from(*c) {
-                Some(Code::Float) => {
-                    let bytes = f32::from_str(n)?;
-                    buffer.extend_from_slice(&bytes.to_be_bytes());
-                },
-                Some(Code::Double) => {
-                    let bytes = f64::from_str(n)?;
-                    buffer.extend_from_slice(&bytes.to_be_bytes());
-                },
-                Some(Code::Unsigned) => {
-                    let bytes = u32::from_str(n)?;
-                    buffer.extend_from_slice(&bytes.to_be_bytes());
-                },
-                Some(Code::UnsignedLong) => {
-                    let bytes = u64::from_str(n)?;
-                    buffer.extend_from_slice(&bytes.to_be_bytes());
-                },
-                Some(Code::Signed) => {
-                    let bytes = i32::from_str(n)?;
-                    buffer.extend_from_slice(&bytes.to_be_bytes());
-                },
-                Some(Code::SignedLong) => {
-                    let bytes = i64::from_str(n)?;
-                    buffer.extend_from_slice(&bytes.to_be_bytes());
-                },
-                None => return Err("Invalid code character".into()),
-            }
+        for c in code.bytes() {
+            let n = iter.next().ok_or("missing value")?;
+            values.push(parse_value(c, n)?);
         }
 
-        match db.append(&mut wtxn, &(text, nanos), &buffer) {
-            Ok(()) => (),
-            Err(Error::Lmdb(LmdbError::KeyExist)) => {
-                return Err("inserted value not ordered".into())
+        match date {
+            Some(date) => {
+                let dt = NaiveDateTime::parse_from_str(date, DATETIME_FORMAT)?;
+                let nanos = dt.timestamp_nanos() as u64;
+                writer.append(text, nanos, &values)?;
             },
-            Err(error) => return Err(error.into()),
+            None => writer.append_now(text, &values, &clock)?,
         }
     }
 
-    wtxn.commit()?;
-
     Ok(())
 }
 
 fn read_from_database(opt: ReadOpt) -> Result<(), MainError> {
-    let env = unsafe { EnvOpenOptions::new()
-        .map_size(10 * 1024 * 1024 * 1024) // 10GB
-        .flag(heed::flags::Flags::MdbRdOnly)
-        .open(opt.database)? };
-
-    let db = match env.open_database::<Key, ByteSlice>(None)? {
-        Some(db) => db,
-        None => return Err("database not found".into()),
-    };
-
+    let reader = Reader::open(opt.database)?;
+    let rtxn = reader.read_txn()?;
 
-    let rtxn = env.read_txn()?;
-
-    let code = match db.first(&rtxn)? {
-        Some((_, code)) => code,
-        None => return Ok(()),
-    };
-
-    let iter = match opt.filter.as_ref() {
+    let iter = match opt.filter.clone() {
         // if the pattern doesn't contain any glob syntax
         Some(pattern) if pattern.as_str() == glob::Pattern::escape(pattern.as_str()) => {
-            let start = (pattern.as_str(), 0);
-            let end = (pattern.as_str(), u64::max_value());
-            db.range(&rtxn, &(start..=end))?
+            reader.range(&rtxn, pattern.as_str())?
         },
-        // skip the first entry (that contains the code)
-        _ => db.range(&rtxn, &(("", 1)..))?,
+        Some(pattern) => reader.filter(&rtxn, pattern)?,
+        None => reader.filter(&rtxn, glob::Pattern::new("*")?)?,
     };
 
-    let codes = code.iter().map(|c| Code::from(*c).unwrap());
-    let codes = SmallVec8::from_iter(codes);
-
     let mut writer = BufWriter::new(io::stdout());
 
     for result in iter {
-        let ((text, nanos), bytes) = result?;
-
-        let dt = {
-            let secs = nanos / ONE_BILLION;
-            let nsecs = nanos % ONE_BILLION;
-            let dt = NaiveDateTime::from_timestamp(secs as i64, nsecs as u32);
+        let record = result?;
 
-            dt.format(DATETIME_FORMAT)
-        };
+        match opt.format {
+            Format::Text => {
+                let dt = {
+                    let secs = record.timestamp / ONE_BILLION;
+                    let nsecs = record.timestamp % ONE_BILLION;
+                    let dt = NaiveDateTime::from_timestamp(secs as i64, nsecs as u32);
 
-        if let Some(pattern) = opt.filter.as_ref() {
-            if !pattern.matches(text) {
-                continue
-            }
-        }
+                    dt.format(DATETIME_FORMAT)
+                };
 
-        write!(&mut writer, "{} {} ", text, dt)?;
+                write!(&mut writer, "{} {} ", record.series, dt)?;
 
-        let mut cursor = Cursor::new(bytes);
-        for (i, code) in codes.iter().enumerate() {
-            match code {
-                Code::Float => {
-                    let value = cursor.read_f32::<BigEndian>()?;
-                    write!(&mut writer, "{}", value)?;
-                },
-                Code::Double => {
-                    let value = cursor.read_f64::<BigEndian>()?;
-                    write!(&mut writer, "{}", value)?;
-                },
-                Code::Unsigned => {
-                    let value = cursor.read_u32::<BigEndian>()?;
-                    write!(&mut writer, "{}", value)?;
-                },
-                Code::UnsignedLong => {
-                    let value = cursor.read_u64::<BigEndian>()?;
-                    write!(&mut writer, "{}", value)?;
-                },
-                Code::Signed => {
-                    let value = cursor.read_i32::<BigEndian>()?;
-                    write!(&mut writer, "{}", value)?;
-                },
-                Code::SignedLong => {
-                    let value = cursor.read_i64::<BigEndian>()?;
+                for (i, value) in record.values.iter().enumerate() {
+                    if i != 0 {
+                        write!(&mut writer, " ")?;
+                    }
                     write!(&mut writer, "{}", value)?;
-                },
-            }
+                }
 
-            if i != codes.len() - 1 {
-                write!(&mut writer, " ")?;
-            }
+                writeln!(&mut writer)?;
+            },
+            Format::Json => {
+                let values: Vec<serde_json::Value> =
+                    record.values.into_iter().map(json_value).collect();
+                let record = json!({
+                    "series": record.series,
+                    "timestamp": record.timestamp,
+                    "values": values,
+                });
+
+                serde_json::to_writer(&mut writer, &record)?;
+                writeln!(&mut writer)?;
+            },
+            Format::Cbor => {
+                let values: Vec<serde_cbor::Value> =
+                    record.values.into_iter().map(cbor_value).collect();
+                let mut map = std::collections::BTreeMap::new();
+                map.insert(
+                    serde_cbor::Value::Text("series".into()),
+                    serde_cbor::Value::Text(record.series),
+                );
+                map.insert(
+                    serde_cbor::Value::Text("timestamp".into()),
+                    serde_cbor::Value::Integer(record.timestamp as i128),
+                );
+                map.insert(serde_cbor::Value::Text("values".into()), serde_cbor::Value::Array(values));
+
+                serde_cbor::to_writer(&mut writer, &serde_cbor::Value::Map(map))?;
+            },
         }
-
-        writeln!(&mut writer)?;
     }
 
     Ok(())
 }
 
 fn infos_of_database(opt: InfosOpt) -> Result<(), MainError> {
-    let env = unsafe { EnvOpenOptions::new()
-        .map_size(10 * 1024 * 1024 * 1024) // 10GB
-        .flag(heed::flags::Flags::MdbRdOnly)
-        .open(opt.database)? };
-
-    let db = match env.open_database::<Key, ByteSlice>(None)? {
-        Some(db) => db,
-        None => return Err("database not found".into()),
-    };
+    let reader = Reader::open(opt.database)?;
+    let rtxn = reader.read_txn()?;
 
-    let rtxn = env.read_txn()?;
-    let code = db.get(&rtxn, &("", 0))?;
-    if let Some(code) = code {
-        let code = str::from_utf8(code)?;
-        println!("values code: {}", code);
+    let mut schemas = reader.schemas(&rtxn)?;
+    schemas.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (series, code) in &schemas {
+        println!("{}: {}", series, std::str::from_utf8(code)?);
     }
 
-    let len = db.len(&rtxn)?;
-    let len = len.saturating_sub(1);
-    println!("number of entries: {}", len);
+    println!("packed: {}", reader.packed(&rtxn)?);
+    println!("number of entries: {}", reader.len(&rtxn)?);
 
     Ok(())
 }